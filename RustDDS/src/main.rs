@@ -3,13 +3,17 @@ use log::{debug, error, trace, LevelFilter};
 use log4rs::{append::console::ConsoleAppender, config::Appender, config::Root, Config};
 
 use rustdds::dds::data_types::DDSDuration;
+use rustdds::dds::data_types::Timestamp;
 use rustdds::dds::data_types::TopicKind;
-use rustdds::dds::qos::policy::{Deadline, Durability, History, Reliability};
-use rustdds::dds::qos::QosPolicyBuilder;
+use rustdds::dds::qos::policy::{
+    Deadline, Durability, History, Ownership, Partition, Reliability, TimeBasedFilter,
+};
+use rustdds::dds::qos::{QosPolicies, QosPolicyBuilder};
 use rustdds::dds::statusevents::StatusEvented;
 use rustdds::dds::traits::Keyed;
 use rustdds::dds::traits::TopicDescription;
-use rustdds::dds::DomainParticipant;
+use rustdds::dds::WriteOptionsBuilder;
+use rustdds::dds::{DomainParticipant, Topic};
 use serde::{Deserialize, Serialize};
 
 use clap::ArgEnum;
@@ -19,7 +23,10 @@ use clap::{App, Arg}; // command line argument processing
 use mio::*; // polling
 use mio_extras::channel; // pollable channel
 
+use futures::{channel::oneshot, select, FutureExt, StreamExt};
+
 use std::io;
+use std::path::{Path, PathBuf};
 
 use rand::prelude::*;
 
@@ -44,9 +51,23 @@ const DA_WIDTH: i32 = 240;
 const DA_HEIGHT: i32 = 270;
 
 const STOP_PROGRAM: Token = Token(0);
-const READER_READY: Token = Token(1);
-const READER_STATUS_READY: Token = Token(2);
-const WRITER_STATUS_READY: Token = Token(3);
+
+// Each topic gets its own block of `TOKENS_PER_TOPIC` mio tokens, starting right after
+// `STOP_PROGRAM`, so several readers/writers can be registered with the same `Poll` and
+// dispatched back to the entity they belong to.
+const TOKENS_PER_TOPIC: usize = 3;
+
+fn reader_ready_token(topic_index: usize) -> Token {
+    Token(1 + TOKENS_PER_TOPIC * topic_index)
+}
+
+fn reader_status_token(topic_index: usize) -> Token {
+    Token(2 + TOKENS_PER_TOPIC * topic_index)
+}
+
+fn writer_status_token(topic_index: usize) -> Token {
+    Token(3 + TOKENS_PER_TOPIC * topic_index)
+}
 
 #[derive(Clap)]
 struct Args {
@@ -54,17 +75,18 @@ struct Args {
     #[clap(short = 'd', long, default_value_t, value_name = "id")]
     domain_id: u16,
 
-    /// Sets the topic name
-    #[clap(short, long, value_name = "name", default_value = "Square")]
-    topic: String,
+    /// Sets the topic name. Repeat to exchange several topics (e.g. -t Square -t Circle)
+    /// within one domain participant: one reader or writer is created per topic.
+    #[clap(short, long, value_name = "name", default_value = "Square", multiple_occurrences = true)]
+    topic: Vec<String>,
 
     /// Color to publish (or filter)
     #[clap(short, long, default_value = "BLUE")]
     color: String,
 
-    /// Set durability
-    #[clap(arg_enum, short = 'D', long, default_value = "v")]
-    durability: DurabilityArg,
+    /// Set durability [default: v]
+    #[clap(arg_enum, short = 'D', long)]
+    durability: Option<DurabilityArg>,
 
     #[clap(subcommand)]
     command: Command,
@@ -72,9 +94,9 @@ struct Args {
     #[clap(arg_enum, long, short)]
     reliability: Option<ReliabilityArg>,
 
-    /// Keep history depth
-    #[clap(short = 'k', long, default_value_t)]
-    history_depth: i32,
+    /// Keep history depth [default: 0]
+    #[clap(short = 'k', long)]
+    history_depth: Option<i32>,
 
     /// Set a 'deadline' with interval (seconds)
     #[clap(short = 'f', long, value_name = "interval")]
@@ -90,10 +112,23 @@ struct Args {
 
     /// Set ownership strength [-1: SHARED]
     #[clap(short, long, value_name = "strength")]
-    ownership_strength: Option<u16>,
+    ownership_strength: Option<i32>,
+
+    /// Run using the async (futures stream) API instead of the mio poll loop
+    #[clap(long = "async")]
+    r#async: bool,
+
+    /// Load a QoS profile from a JSON or RON file (selected by extension).
+    /// CLI QoS flags override the values it contains.
+    #[clap(long, value_name = "path")]
+    qos_file: Option<PathBuf>,
+
+    /// Write the effective QoS profile (file + CLI overrides) to stdout and exit
+    #[clap(long)]
+    dump_qos: bool,
 }
 
-#[derive(ArgEnum)]
+#[derive(ArgEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 enum DurabilityArg {
     V,
     L,
@@ -108,48 +143,222 @@ enum Command {
 
     /// Act as subscriber
     Subscribe,
+
+    /// Run a matched writer and reader on this topic in one process and
+    /// report end-to-end latency and reception lag
+    Bench {
+        /// Number of samples to publish before reporting results
+        #[clap(short, long, default_value_t = 1000)]
+        count: u32,
+    },
 }
 
-#[derive(ArgEnum)]
+#[derive(ArgEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 enum ReliabilityArg {
     Reliable,
     BestEffort,
 }
 
 impl Args {
-    pub fn reliability(&self) -> Reliability {
-        match self.reliability {
-            Some(ReliabilityArg::Reliable) => Reliability::Reliable {
-                max_blocking_time: DDSDuration::DURATION_ZERO,
-            },
-            Some(ReliabilityArg::BestEffort) | None => Reliability::BestEffort,
+    pub fn domain_participant(&self) -> Result<DomainParticipant, rustdds::dds::error::Error> {
+        DomainParticipant::new(self.domain_id)
+    }
+
+    /// Builds the effective QoS profile: the `--qos-file` profile (if any), with every QoS
+    /// flag the user actually passed on the command line overriding the matching field.
+    pub fn effective_qos_config(&self) -> QosConfig {
+        let base = self
+            .qos_file
+            .as_deref()
+            .map(QosConfig::load)
+            .unwrap_or_default();
+        self.apply_qos_overrides(base)
+    }
+
+    /// Applies the QoS flags actually given on the command line on top of `config`, leaving
+    /// any field the user didn't pass a flag for untouched. Split out from
+    /// `effective_qos_config` so the override precedence can be unit tested without needing
+    /// a `--qos-file` on disk.
+    fn apply_qos_overrides(&self, mut config: QosConfig) -> QosConfig {
+        if self.reliability.is_some() {
+            config.reliability = self.reliability;
         }
+        if self.durability.is_some() {
+            config.durability = self.durability;
+        }
+        if self.history_depth.is_some() {
+            config.history_depth = self.history_depth;
+        }
+        if let Some(deadline) = self.deadline {
+            config.deadline = Some(DurationConfig::from_frac_seconds(deadline));
+        }
+        if let Some(partition) = &self.partition {
+            config.partition = Some(partition.clone());
+        }
+        if let Some(interval) = self.interval {
+            config.interval = Some(DurationConfig::from_frac_seconds(interval));
+        }
+        if self.ownership_strength.is_some() {
+            config.ownership_strength = self.ownership_strength;
+        }
+
+        config
     }
+}
 
-    pub fn durability(&self) -> Durability {
-        match self.durability {
-            DurabilityArg::V => Durability::Volatile,
-            DurabilityArg::L => Durability::TransientLocal,
-            DurabilityArg::T => Durability::Transient,
-            DurabilityArg::P => Durability::Persistent,
+/// Config-file representation of a `DDSDuration`: either the literal string `"infinite"`, or
+/// `{ "secs": N, "nanos": M }`. `DDSDuration`'s derived serde impl does not round-trip through
+/// JSON/RON in a form anyone would want to hand-edit, so this converts to/from it explicitly.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+enum DurationConfig {
+    Infinite(InfiniteTag),
+    Finite { secs: u32, nanos: u32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum InfiniteTag {
+    #[serde(rename = "infinite")]
+    Infinite,
+}
+
+impl DurationConfig {
+    fn from_frac_seconds(seconds: f64) -> DurationConfig {
+        DurationConfig::Finite {
+            secs: seconds.trunc() as u32,
+            nanos: (seconds.fract() * 1_000_000_000.0).round() as u32,
         }
     }
 
-    pub fn history_depth(&self) -> History {
-        match self.history_depth {
-            x if x < 0 => History::KeepAll,
-            x => History::KeepLast { depth: x },
+    fn to_dds_duration(self) -> DDSDuration {
+        match self {
+            DurationConfig::Infinite(_) => DDSDuration::DURATION_INFINITE,
+            DurationConfig::Finite { secs, nanos } => {
+                DDSDuration::from_frac_seconds(secs as f64 + nanos as f64 / 1_000_000_000.0)
+            }
         }
     }
+}
 
-    pub fn deadline(&self) -> Option<Deadline> {
-        self.deadline
-            .map(|d| Deadline(DDSDuration::from_frac_seconds(d)))
+/// On-disk representation of a full QoS profile, loaded with `--qos-file` and written back
+/// out with `--dump-qos`. Every field is only set when the file or a CLI flag actually
+/// specifies it, so a value loaded from the file survives unless the matching CLI flag is
+/// also passed. For anything left unset, `build_qos_policies` uses BestEffort reliability,
+/// Volatile durability and KeepLast history of depth 0, and leaves the other policies out.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct QosConfig {
+    reliability: Option<ReliabilityArg>,
+    durability: Option<DurabilityArg>,
+    history_depth: Option<i32>,
+    deadline: Option<DurationConfig>,
+    partition: Option<String>,
+    interval: Option<DurationConfig>,
+    ownership_strength: Option<i32>,
+}
+
+impl QosConfig {
+    fn load(path: &Path) -> QosConfig {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("failed to read QoS file {:?}: {:?}", path, e));
+        if path.extension().and_then(|ext| ext.to_str()) == Some("ron") {
+            ron::from_str(&contents)
+                .unwrap_or_else(|e| panic!("failed to parse QoS file {:?}: {:?}", path, e))
+        } else {
+            serde_json::from_str(&contents)
+                .unwrap_or_else(|e| panic!("failed to parse QoS file {:?}: {:?}", path, e))
+        }
     }
 
-    pub fn domain_participant(&self) -> Result<DomainParticipant, rustdds::dds::error::Error> {
-        DomainParticipant::new(self.domain_id)
+    /// Serializes this profile, choosing RON or JSON to match `qos_file`'s extension
+    /// (defaulting to JSON when there is no `--qos-file` to take a hint from).
+    fn dump(&self, qos_file: Option<&Path>) -> String {
+        let as_ron = qos_file
+            .and_then(Path::extension)
+            .and_then(|ext| ext.to_str())
+            == Some("ron");
+        if as_ron {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .unwrap_or_else(|e| panic!("failed to serialize QoS profile: {:?}", e))
+        } else {
+            serde_json::to_string_pretty(self)
+                .unwrap_or_else(|e| panic!("failed to serialize QoS profile: {:?}", e))
+        }
     }
+
+    fn build_qos_policies(&self) -> QosPolicies {
+        let mut qos_b = QosPolicyBuilder::new()
+            .reliability(match self.reliability {
+                Some(ReliabilityArg::Reliable) => Reliability::Reliable {
+                    max_blocking_time: DDSDuration::DURATION_ZERO,
+                },
+                Some(ReliabilityArg::BestEffort) | None => Reliability::BestEffort,
+            })
+            .durability(match self.durability {
+                Some(DurabilityArg::L) => Durability::TransientLocal,
+                Some(DurabilityArg::T) => Durability::Transient,
+                Some(DurabilityArg::P) => Durability::Persistent,
+                Some(DurabilityArg::V) | None => Durability::Volatile,
+            })
+            .history(match self.history_depth.unwrap_or(0) {
+                x if x < 0 => History::KeepAll,
+                x => History::KeepLast { depth: x },
+            });
+
+        if let Some(deadline) = self.deadline {
+            qos_b = qos_b.deadline(Deadline(deadline.to_dds_duration()));
+        }
+        if let Some(partition) = &self.partition {
+            qos_b = qos_b.partition(Partition {
+                partitions: vec![partition.clone()],
+            });
+        }
+        if let Some(interval) = self.interval {
+            qos_b = qos_b.time_based_filter(TimeBasedFilter {
+                minimum_separation: interval.to_dds_duration(),
+            });
+        }
+        if let Some(strength) = self.ownership_strength {
+            qos_b = qos_b.ownership(if strength < 0 {
+                Ownership::Shared
+            } else {
+                Ownership::Exclusive { strength }
+            });
+        }
+
+        qos_b.build()
+    }
+}
+
+fn create_topic(domain_participant: &DomainParticipant, name: &str, qos: &QosPolicies) -> Topic {
+    let topic = domain_participant
+        .create_topic(name, "ShapeType", qos, TopicKind::WithKey)
+        .unwrap_or_else(|e| panic!("create_topic failed: {:?}", e));
+    println!(
+        "Topic name is {}. Type is {}.",
+        topic.get_name(),
+        topic.get_type().name()
+    );
+    topic
+}
+
+/// Per-topic state for the mio poll loop: either a writer moving and publishing its own
+/// shape, or a reader delivering the samples it receives.
+enum TopicRole {
+    Writer {
+        writer: rustdds::dds::DataWriter<Shape>,
+        shape_sample: Shape,
+        x_vel: i32,
+        y_vel: i32,
+        last_write: Instant,
+    },
+    Reader {
+        reader: rustdds::dds::DataReader<Shape>,
+    },
+}
+
+struct TopicEntity {
+    topic: Topic,
+    role: TopicRole,
 }
 
 fn main() {
@@ -174,42 +383,40 @@ fn main() {
 
     let args = Args::parse();
 
+    let qos_config = args.effective_qos_config();
+
+    if args.dump_qos {
+        println!("{}", qos_config.dump(args.qos_file.as_deref()));
+        return;
+    }
+
     let domain_participant = args
         .domain_participant()
         .unwrap_or_else(|e| panic!("DomainParticipant construction failed: {:?}", e));
 
-    let mut qos_b = QosPolicyBuilder::new()
-        .reliability(args.reliability())
-        .durability(args.durability())
-        .history(args.history_depth());
-
-    if let Some(deadline) = args.deadline() {
-        qos_b = qos_b.deadline(deadline);
+    let qos = qos_config.build_qos_policies();
+
+    if let Command::Bench { count } = args.command {
+        assert!(
+            args.topic.len() == 1,
+            "bench mode only supports a single --topic; got {:?}",
+            args.topic
+        );
+        let topic = create_topic(&domain_participant, &args.topic[0], &qos);
+        run_bench(&args, &domain_participant, &topic, &qos, count);
+        return;
     }
 
-    assert!(
-        args.partition.is_none(),
-        "QoS policy Partition is not yet implemented."
-    );
-    assert!(
-        args.interval.is_none(),
-        "QoS policy Time Based Filter is not yet implemented."
-    );
-    assert!(
-        args.ownership_strength.is_none(),
-        "QoS policy Ownership Strength is not yet implemented."
-    );
-
-    let qos = qos_b.build();
-
-    let topic = domain_participant
-        .create_topic(&args.topic, "ShapeType", &qos, TopicKind::WithKey)
-        .unwrap_or_else(|e| panic!("create_topic failed: {:?}", e));
-    println!(
-        "Topic name is {}. Type is {}.",
-        topic.get_name(),
-        topic.get_type().name()
-    );
+    if args.r#async {
+        assert!(
+            args.topic.len() == 1,
+            "--async only supports a single --topic; got {:?}",
+            args.topic
+        );
+        let topic = create_topic(&domain_participant, &args.topic[0], &qos);
+        smol::block_on(run_async(args, domain_participant, topic, qos));
+        return;
+    }
 
     // Set Ctrl-C handler
     let (stop_sender, stop_receiver) = channel::channel();
@@ -221,7 +428,7 @@ fn main() {
     println!("Press Ctrl-C to quit.");
 
     let poll = Poll::new().unwrap();
-    let mut events = Events::with_capacity(4);
+    let mut events = Events::with_capacity(4 + TOKENS_PER_TOPIC * args.topic.len());
 
     poll.register(
         &stop_receiver,
@@ -231,139 +438,424 @@ fn main() {
     )
     .unwrap();
 
-    match args.command {
-        Command::Publish => todo!(),
-        Command::Subscribe => todo!(),
+    // One reader or writer per `--topic`, sharing this domain participant. Each entity's
+    // mio tokens are derived from its index, so events route back to the right one.
+    let mut entities: Vec<TopicEntity> = Vec::with_capacity(args.topic.len());
+
+    for (topic_index, topic_name) in args.topic.iter().enumerate() {
+        let topic = create_topic(&domain_participant, topic_name, &qos);
+
+        let role = match args.command {
+            Command::Publish => {
+                debug!("Publisher for topic {}", topic_name);
+                let publisher = domain_participant
+                    .create_publisher(&qos)
+                    .unwrap_or_else(|e| panic!("create_publisher failed: {:?}", e));
+                let writer = publisher
+                    .create_datawriter_CDR::<Shape>(topic.clone(), None) // None = get qos policy from publisher
+                    .unwrap_or_else(|e| panic!("create_datawriter failed: {:?}", e));
+                poll.register(
+                    writer.as_status_evented(),
+                    writer_status_token(topic_index),
+                    Ready::readable(),
+                    PollOpt::edge(),
+                )
+                .unwrap();
+
+                let mut random_gen = thread_rng();
+                // a bit complicated lottery to ensure we do not end up with zero velocity.
+                let x_vel = if random() {
+                    random_gen.gen_range(1..5)
+                } else {
+                    random_gen.gen_range(-5..-1)
+                };
+                let y_vel = if random() {
+                    random_gen.gen_range(1..5)
+                } else {
+                    random_gen.gen_range(-5..-1)
+                };
+
+                TopicRole::Writer {
+                    writer,
+                    shape_sample: Shape {
+                        color: args.color.clone(),
+                        x: 0,
+                        y: 0,
+                        shapesize: 21,
+                    },
+                    x_vel,
+                    y_vel,
+                    last_write: Instant::now(),
+                }
+            }
+            Command::Subscribe => {
+                debug!("Subscriber for topic {}", topic_name);
+                let subscriber = domain_participant
+                    .create_subscriber(&qos)
+                    .unwrap_or_else(|e| panic!("create_subscriber failed: {:?}", e));
+                let reader = subscriber
+                    .create_datareader_CDR::<Shape>(topic.clone(), Some(qos.clone()))
+                    .unwrap_or_else(|e| panic!("create_datareader failed: {:?}", e));
+                poll.register(
+                    &reader,
+                    reader_ready_token(topic_index),
+                    Ready::readable(),
+                    PollOpt::edge(),
+                )
+                .unwrap();
+                poll.register(
+                    reader.as_status_evented(),
+                    reader_status_token(topic_index),
+                    Ready::readable(),
+                    PollOpt::edge(),
+                )
+                .unwrap();
+                debug!("Created DataReader for topic {}", topic_name);
+                TopicRole::Reader { reader }
+            }
+            Command::Bench { .. } => unreachable!("bench mode returns before reaching the poll loop"),
+        };
+
+        entities.push(TopicEntity { topic, role });
     }
 
-    /*   let mut writer_opt =
-      if is_publisher {
-        debug!("Publisher");
-        let publisher = domain_participant.create_publisher(&qos).unwrap();
-        let mut writer = publisher
-              .create_datawriter_CDR::<Shape>( topic.clone(), None) // None = get qos policy from publisher
-              .unwrap();
-        poll.register(writer.as_status_evented(), WRITER_STATUS_READY, Ready::readable(), PollOpt::edge())
-          .unwrap();
-        Some(writer)
-      } else { None };
-
-    let mut reader_opt =
-      if is_subscriber {
-        debug!("Subscriber");
-        let subscriber = domain_participant.create_subscriber(&qos).unwrap();
-        let mut reader = subscriber
-          .create_datareader_CDR::<Shape>( topic.clone(), Some(qos) )
-          .unwrap();
-        poll.register(&reader, READER_READY, Ready::readable(),PollOpt::edge())
-          .unwrap();
-        poll.register(reader.as_status_evented(), READER_STATUS_READY, Ready::readable(), PollOpt::edge())
-          .unwrap();
-        debug!("Created DataReader");
-        Some(reader)
-      } else { None };
-
-    let mut shape_sample = Shape { color: color.to_string(), x: 0, y: 0, shapesize: 21 };
-    let mut random_gen = thread_rng();
-    // a bit complicated lottery to ensure we do not end up with zero velocity.
-    let mut x_vel = if random() { random_gen.gen_range(1..5) } else { random_gen.gen_range(-5..-1) };
-    let mut y_vel = if random() { random_gen.gen_range(1..5) } else { random_gen.gen_range(-5..-1) };
-
-    let mut last_write = Instant::now();
-
-      loop {
-          poll
-              .poll(&mut events, Some(Duration::from_millis(200)))
-              .unwrap();
-          for event in &events {
-              match event.token() {
-                  STOP_PROGRAM => {
-                      match stop_receiver.try_recv() {
-                          Ok(_) => {
-                            println!("Done.");
-                            return
-                          }
-                          Err(_) => { /* Can this even happen? */ }
-                      }
-                  }
-          READER_READY => {
-              match reader_opt {
-                Some(ref mut reader) => {
-                  loop {
-                    trace!("DataReader triggered");
+    loop {
+        poll.poll(&mut events, Some(Duration::from_millis(200)))
+            .unwrap();
+        for event in &events {
+            let token = event.token();
+            if token == STOP_PROGRAM {
+                match stop_receiver.try_recv() {
+                    Ok(_) => {
+                        println!("Done.");
+                        return;
+                    }
+                    Err(_) => { /* Can this even happen? */ }
+                }
+                continue;
+            }
+
+            let topic_index = (token.0 - 1) / TOKENS_PER_TOPIC;
+            let entity = match entities.get_mut(topic_index) {
+                Some(entity) => entity,
+                None => {
+                    println!("Polled event is {:?}. WTF?", token);
+                    continue;
+                }
+            };
+            let topic_name = entity.topic.get_name().to_string();
+
+            match ((token.0 - 1) % TOKENS_PER_TOPIC, &mut entity.role) {
+                (0, TopicRole::Reader { reader }) => loop {
+                    trace!("DataReader triggered for topic {}", topic_name);
                     match reader.take_next_sample() {
-                      Ok(Some(sample)) =>
-                        match sample.into_value() {
-                          Ok(sample) =>
-                            println!("{:10.10} {:10.10} {:3.3} {:3.3} [{}]",
-                                      topic.get_name(),
-                                      sample.color,
-                                      sample.x,
-                                      sample.y,
-                                      sample.shapesize,
-                                      ),
-                          Err(key) =>
-                            println!("Disposed key {:?}", key),
-                          },
-                      Ok(None) => break, // no more data
-                      Err(e) => println!("DataReader error {:?}", e),
+                        Ok(Some(sample)) => match sample.into_value() {
+                            Ok(sample) => {
+                                // RustDDS delivers every instance published on the topic, and
+                                // the core crate does not yet expose a ContentFilteredTopic,
+                                // so apply the `--color` filter here until it does.
+                                if sample.color != args.color {
+                                    continue;
+                                }
+                                println!(
+                                    "{:10.10} {:10.10} {:3.3} {:3.3} [{}]",
+                                    topic_name,
+                                    sample.color,
+                                    sample.x,
+                                    sample.y,
+                                    sample.shapesize,
+                                )
+                            }
+                            Err(key) => println!("Disposed key {:?}", key),
+                        },
+                        Ok(None) => break, // no more data
+                        Err(e) => println!("DataReader error {:?}", e),
                     } // match
-                  }
+                },
+                (1, TopicRole::Reader { reader }) => {
+                    while let Some(status) = reader.try_recv_status() {
+                        println!("DataReader status for topic {}: {:?}", topic_name, status);
+                    }
+                }
+                (2, TopicRole::Writer { writer, .. }) => {
+                    while let Some(status) = writer.try_recv_status() {
+                        println!("DataWriter status for topic {}: {:?}", topic_name, status);
+                    }
+                }
+                _ => {
+                    println!("Polled event is {:?}. WTF?", token);
                 }
-                None => { error!("Where is my reader?"); }
-              }
             }
-          READER_STATUS_READY => {
-            match reader_opt {
-              Some(ref mut reader) => {
-                while let Some(status) = reader.try_recv_status() {
-                  println!("DataReader status: {:?}", status);
+        }
+
+        for entity in &mut entities {
+            if let TopicRole::Writer {
+                writer,
+                shape_sample,
+                x_vel,
+                y_vel,
+                last_write,
+            } = &mut entity.role
+            {
+                let r = move_shape(shape_sample.clone(), *x_vel, *y_vel);
+                *shape_sample = r.0;
+                *x_vel = r.1;
+                *y_vel = r.2;
+
+                // write to DDS
+                trace!("Writing shape color {}", &shape_sample.color);
+                let now = Instant::now();
+                if *last_write + Duration::from_millis(200) < now {
+                    writer
+                        .write(shape_sample.clone(), None)
+                        .unwrap_or_else(|e| error!("DataWriter write failed: {:?}", e));
+                    *last_write = now;
                 }
-              }
-              None => { error!("Where is my reader?"); }
             }
-          }
-
-                  WRITER_STATUS_READY => {
-            match writer_opt {
-              Some(ref mut writer) => {
-                          while let Some(status) = writer.try_recv_status() {
-                              println!("DataWriter status: {:?}", status);
-                          }
-              }
-              None => { error!("Where is my writer?"); }
+        }
+    }
+}
+
+/// Runs the shapes client using RustDDS's async (futures stream) API instead
+/// of the mio poll loop in `main`. This is the idiomatic integration point
+/// for users embedding DDS in an async application.
+async fn run_async(
+    args: Args,
+    domain_participant: DomainParticipant,
+    topic: Topic,
+    qos: QosPolicies,
+) {
+    // Set Ctrl-C handler, forwarding into a future instead of a mio channel.
+    let (stop_sender, stop_receiver) = oneshot::channel();
+    let mut stop_sender = Some(stop_sender);
+    ctrlc::set_handler(move || {
+        if let Some(stop_sender) = stop_sender.take() {
+            stop_sender.send(()).unwrap_or(())
+            // ignore errors, as we are quitting anyway
+        }
+    })
+    .expect("Error setting Ctrl-C handler");
+    println!("Press Ctrl-C to quit.");
+
+    let mut stop_receiver = stop_receiver.fuse();
+
+    match args.command {
+        Command::Publish => {
+            debug!("Publisher");
+            let publisher = domain_participant
+                .create_publisher(&qos)
+                .unwrap_or_else(|e| panic!("create_publisher failed: {:?}", e));
+            let mut writer = publisher
+                .create_async_datawriter_CDR::<Shape>(topic.clone(), None)
+                .unwrap_or_else(|e| panic!("create_datawriter failed: {:?}", e));
+
+            let mut shape_sample = Shape {
+                color: args.color.clone(),
+                x: 0,
+                y: 0,
+                shapesize: 21,
+            };
+            let mut random_gen = thread_rng();
+            // a bit complicated lottery to ensure we do not end up with zero velocity.
+            let mut x_vel = if random() {
+                random_gen.gen_range(1..5)
+            } else {
+                random_gen.gen_range(-5..-1)
+            };
+            let mut y_vel = if random() {
+                random_gen.gen_range(1..5)
+            } else {
+                random_gen.gen_range(-5..-1)
+            };
+
+            let mut ticker = smol::Timer::interval(Duration::from_millis(200));
+
+            loop {
+                select! {
+                    _ = stop_receiver => {
+                        println!("Done.");
+                        return;
+                    }
+                    _ = ticker.next().fuse() => {
+                        let r = move_shape(shape_sample, x_vel, y_vel);
+                        shape_sample = r.0;
+                        x_vel = r.1;
+                        y_vel = r.2;
+
+                        trace!("Writing shape color {}", &args.color);
+                        writer
+                            .write(shape_sample.clone(), None)
+                            .await
+                            .unwrap_or_else(|e| error!("DataWriter write failed: {:?}", e));
+                    }
+                }
             }
-                  }
-                  other_token => {
-                      println!("Polled event is {:?}. WTF?", other_token);
-                  }
-              }
-          }
-
-      let r = move_shape(shape_sample,x_vel,y_vel);
-      shape_sample = r.0;
-      x_vel = r.1;
-      y_vel = r.2;
-
-      // write to DDS
-      trace!("Writing shape color {}", &color);
-      match writer_opt {
-        Some(ref mut writer) => {
-          let now = Instant::now();
-          if last_write + Duration::from_millis(200) < now {
-            writer.write( shape_sample.clone() , None)
-              .unwrap_or_else(|e| error!("DataWriter write failed: {:?}",e));
-            last_write = now;
-          }
         }
-        None => {
-          if is_publisher {
-            error!("Where is my writer?");
-          } else { /* never mind */ }
+        Command::Subscribe => {
+            debug!("Subscriber");
+            let subscriber = domain_participant
+                .create_subscriber(&qos)
+                .unwrap_or_else(|e| panic!("create_subscriber failed: {:?}", e));
+            let reader = subscriber
+                .create_async_datareader_CDR::<Shape>(topic.clone(), Some(qos))
+                .unwrap_or_else(|e| panic!("create_datareader failed: {:?}", e));
+            debug!("Created DataReader");
+
+            let mut sample_stream = reader.async_sample_stream().fuse();
+            let mut status_stream = reader.as_async_status_stream().fuse();
+
+            loop {
+                select! {
+                    _ = stop_receiver => {
+                        println!("Done.");
+                        return;
+                    }
+                    sample = sample_stream.next() => {
+                        match sample {
+                            Some(Ok(sample)) => match sample.into_value() {
+                                Ok(sample) => {
+                                    // See the mio poll loop in `main` for why this filter is
+                                    // applied client-side rather than via ContentFilteredTopic.
+                                    if sample.color != args.color {
+                                        continue;
+                                    }
+                                    println!(
+                                        "{:10.10} {:10.10} {:3.3} {:3.3} [{}]",
+                                        topic.get_name(),
+                                        sample.color,
+                                        sample.x,
+                                        sample.y,
+                                        sample.shapesize,
+                                    )
+                                }
+                                Err(key) => println!("Disposed key {:?}", key),
+                            },
+                            Some(Err(e)) => println!("DataReader error {:?}", e),
+                            None => {
+                                println!("Done.");
+                                return;
+                            }
+                        }
+                    }
+                    status = status_stream.next() => {
+                        if let Some(status) = status {
+                            println!("DataReader status: {:?}", status);
+                        }
+                    }
+                }
+            }
         }
-      }
+        Command::Bench { .. } => unreachable!("bench mode returns before reaching run_async"),
+    }
+}
 
-      } // loop */
+/// How long `run_bench` will wait without receiving a single sample before giving up and
+/// reporting partial results, rather than spinning forever on a dropped or unmatched sample.
+const BENCH_STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates a matched writer and reader on `topic` within this process, publishes `count`
+/// samples stamped with the DDS source timestamp, and reports end-to-end latency and
+/// reception lag statistics once every sample has been received, or once no sample has
+/// arrived for `BENCH_STALL_TIMEOUT` (then for the samples received so far). Accepts the same QoS
+/// flags as `Publish`/`Subscribe`, so Reliability/History combinations can be swept.
+fn run_bench(
+    args: &Args,
+    domain_participant: &DomainParticipant,
+    topic: &Topic,
+    qos: &QosPolicies,
+    count: u32,
+) {
+    let publisher = domain_participant
+        .create_publisher(qos)
+        .unwrap_or_else(|e| panic!("create_publisher failed: {:?}", e));
+    let mut writer = publisher
+        .create_datawriter_CDR::<Shape>(topic.clone(), None)
+        .unwrap_or_else(|e| panic!("create_datawriter failed: {:?}", e));
+
+    let subscriber = domain_participant
+        .create_subscriber(qos)
+        .unwrap_or_else(|e| panic!("create_subscriber failed: {:?}", e));
+    let mut reader = subscriber
+        .create_datareader_CDR::<Shape>(topic.clone(), Some(qos.clone()))
+        .unwrap_or_else(|e| panic!("create_datareader failed: {:?}", e));
+
+    let shape_sample = Shape {
+        color: args.color.clone(),
+        x: 0,
+        y: 0,
+        shapesize: 21,
+    };
+
+    let mut latencies = Vec::with_capacity(count as usize);
+    let mut published = 0u32;
+    let mut received = 0u32;
+    let mut max_gap = 0u32;
+
+    println!("Running bench: {} samples.", count);
+
+    // If samples stop arriving altogether (e.g. BestEffort dropping the last datagram, or
+    // a mismatched QoS preventing the reader and writer from ever matching), bail out and
+    // report whatever was collected instead of spinning forever.
+    let mut last_progress = Instant::now();
+
+    while received < count {
+        if published < count {
+            let write_options = WriteOptionsBuilder::new()
+                .source_timestamp(Timestamp::now())
+                .build();
+            writer
+                .write_with_options(shape_sample.clone(), write_options)
+                .unwrap_or_else(|e| panic!("DataWriter write failed: {:?}", e));
+            published += 1;
+        }
+
+        match reader.take_next_sample() {
+            Ok(Some(sample)) => {
+                let source_timestamp = sample.sample_info().source_timestamp();
+                match sample.into_value() {
+                    Ok(_value) => {
+                        received += 1;
+                        last_progress = Instant::now();
+                        if let Some(source_timestamp) = source_timestamp {
+                            latencies.push(Timestamp::now().duration_since(source_timestamp));
+                        }
+                    }
+                    Err(_key) => { /* disposed key, not a sample: ignore for bench purposes */ }
+                }
+            }
+            Ok(None) => { /* no data yet; loop back around to write/read again */ }
+            Err(e) => println!("DataReader error {:?}", e),
+        }
+
+        max_gap = max_gap.max(published.saturating_sub(received));
+
+        if last_progress.elapsed() > BENCH_STALL_TIMEOUT {
+            println!(
+                "Bench stalled: no samples received for {:?}; reporting partial results for \
+                 {}/{} samples.",
+                BENCH_STALL_TIMEOUT, received, count
+            );
+            break;
+        }
+    }
+
+    latencies.sort_unstable();
+    let min = latencies.first().copied().unwrap_or_default();
+    let max = latencies.last().copied().unwrap_or_default();
+    let median = latencies
+        .get(latencies.len() / 2)
+        .copied()
+        .unwrap_or_default();
+    let p99 = latencies
+        .get(latencies.len() * 99 / 100)
+        .copied()
+        .unwrap_or_default();
+
+    println!(
+        "Bench done: {} samples. Latency min={:?} median={:?} p99={:?} max={:?}. Observed \
+         steady-state gap (published - received) = {} samples.",
+        received, min, median, p99, max, max_gap
+    );
 }
 
 fn move_shape(shape: Shape, xv: i32, yv: i32) -> (Shape, i32, i32) {
@@ -401,3 +893,69 @@ fn move_shape(shape: Shape, xv: i32, yv: i32) -> (Shape, i32, i32) {
         yv_new,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_config_infinite_round_trips_through_json() {
+        let original = DurationConfig::Infinite(InfiniteTag::Infinite);
+        let json = serde_json::to_string(&original).unwrap();
+        assert_eq!(json, "\"infinite\"");
+        let parsed: DurationConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn duration_config_finite_round_trips_through_json() {
+        let original = DurationConfig::from_frac_seconds(1.5);
+        let json = serde_json::to_string(&original).unwrap();
+        let parsed: DurationConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn duration_config_infinite_round_trips_through_ron() {
+        let original = DurationConfig::Infinite(InfiniteTag::Infinite);
+        let encoded = ron::to_string(&original).unwrap();
+        let parsed: DurationConfig = ron::from_str(&encoded).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn duration_config_finite_round_trips_through_ron() {
+        let original = DurationConfig::from_frac_seconds(2.25);
+        let encoded = ron::to_string(&original).unwrap();
+        let parsed: DurationConfig = ron::from_str(&encoded).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn apply_qos_overrides_keeps_file_value_when_cli_flag_absent() {
+        let args = Args::parse_from(["shapes_demo", "subscribe"]);
+        let file_config = QosConfig {
+            durability: Some(DurabilityArg::L),
+            partition: Some("file-partition".to_string()),
+            ..QosConfig::default()
+        };
+
+        let merged = args.apply_qos_overrides(file_config);
+
+        assert_eq!(merged.durability, Some(DurabilityArg::L));
+        assert_eq!(merged.partition.as_deref(), Some("file-partition"));
+    }
+
+    #[test]
+    fn apply_qos_overrides_overrides_file_value_when_cli_flag_present() {
+        let args = Args::parse_from(["shapes_demo", "-D", "v", "subscribe"]);
+        let file_config = QosConfig {
+            durability: Some(DurabilityArg::L),
+            ..QosConfig::default()
+        };
+
+        let merged = args.apply_qos_overrides(file_config);
+
+        assert_eq!(merged.durability, Some(DurabilityArg::V));
+    }
+}